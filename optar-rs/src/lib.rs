@@ -1,5 +1,6 @@
 extern crate image;
 extern crate num;
+use std::io::Write;
 use std::str::FromStr;
 
 #[derive(Copy, Clone, Debug)] pub enum FecOrder { Golay, Hamming(u8) }
@@ -11,6 +12,11 @@ impl Default for FecOrder { fn default() -> Self { FecOrder::Golay } }
 impl From<u8> for FecOrder { fn from(x: u8) -> FecOrder { if x == 1 { FecOrder::Golay } else { FecOrder::Hamming(x) } } }
 impl Into<u8> for FecOrder { fn into(self) -> u8 { match self { FecOrder::Golay => 1, FecOrder::Hamming(x) => x } } }
 
+#[derive(Copy, Clone, Debug, PartialEq)] pub enum Compression { None, Deflate }
+impl Default for Compression { fn default() -> Self { Compression::None } }
+impl From<u8> for Compression { fn from(x: u8) -> Compression { if x == 0 { Compression::None } else { Compression::Deflate } } }
+impl Into<u8> for Compression { fn into(self) -> u8 { match self { Compression::None => 0, Compression::Deflate => 1 } } }
+
 pub struct Settings {
     border: u64, /* In pixels. Thickness of the border */
     chalf: u64, /* Size of the cross half. Size of the cross is CHALF*2 x CHALF*2. */
@@ -20,16 +26,18 @@ pub struct Settings {
     xcrosses: u64, /* Number of crosses horizontally */
     ycrosses: u64, /* Number of crosses vertically */
     fec_order: FecOrder,
+    compress: Compression, /* Whether the payload is zlib-compressed before FEC */
 }
 
 impl Default for Settings { fn default() -> Settings {
-    Settings { border: 2, chalf: 3, cpitch: 24, text_width: 13, text_height: 24, xcrosses: 67, ycrosses: 87, fec_order: FecOrder::Golay }
+    Settings { border: 2, chalf: 3, cpitch: 24, text_width: 13, text_height: 24, xcrosses: 67, ycrosses: 87, fec_order: FecOrder::Golay, compress: Compression::None }
 } }
 impl FromStr for Settings {
     type Err = std::num::ParseIntError;
     fn from_str(s: &str) -> std::result::Result<Settings, Self::Err> {
         let components : Vec<&str> = s.split("-").collect();
-        Ok(Settings { xcrosses: components[1].parse()?, ycrosses: components[2].parse()?, cpitch: components[3].parse()?, chalf: components[4].parse()?, fec_order: components[5].parse::<u8>()?.into(), border: components[6].parse()?, text_height: components[7].parse()?, .. Settings::default() })
+        if components.len() < 9 { "".parse::<u8>()?; }
+        Ok(Settings { xcrosses: components[1].parse()?, ycrosses: components[2].parse()?, cpitch: components[3].parse()?, chalf: components[4].parse()?, fec_order: components[5].parse::<u8>()?.into(), border: components[6].parse()?, text_height: components[7].parse()?, compress: components[8].parse::<u8>()?.into(), .. Settings::default() })
     }
 }
 
@@ -63,6 +71,15 @@ impl Settings {
     fn net_bits(&self) -> u64 { self.fec_syms()*self.fec_order.small_bits() }
     fn used_bits(&self) -> u64 { self.fec_syms()*self.fec_order.large_bits() }
 
+    /* The canonical dash-separated parameter string, the exact form FromStr
+     * parses back. The leading tag occupies component 0, which FromStr skips. */
+    fn format_string(&self) -> String {
+        let fec: u8 = self.fec_order.into();
+        let compress: u8 = self.compress.into();
+        format!("optar-{}-{}-{}-{}-{}-{}-{}-{}",
+            self.xcrosses, self.ycrosses, self.cpitch, self.chalf, fec, self.border, self.text_height, compress)
+    }
+
     /* Coordinates don't count with the border - 0,0 is upper left corner of the
      * first cross! */
     fn is_cross(&self, x: u64, y: u64) -> bool {
@@ -103,6 +120,63 @@ impl Settings {
     }
 }
 
+/* CRC-32 with the standard reflected polynomial 0xEDB88320. */
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut table = [0u32; 256];
+    for (n, slot) in table.iter_mut().enumerate() {
+        let mut c = n as u32;
+        for _ in 0..8 { c = if c & 1 != 0 { 0xEDB88320 ^ (c >> 1) } else { c >> 1 }; }
+        *slot = c;
+    }
+    let mut crc = 0xFFFFFFFFu32;
+    for &b in data { crc = (crc >> 8) ^ table[((crc ^ b as u32) & 0xFF) as usize]; }
+    crc ^ 0xFFFFFFFF
+}
+
+/* Pack a stream of channel bits MSB-first into bytes, filling the low end of
+ * the final partial byte with zeroes. Both the encoder and decoder feed their channel
+ * bits through this so the CRC is taken over byte-identical data on each side. */
+fn pack_bits(bits: impl Iterator<Item = bool>) -> Vec<u8> {
+    let (mut cur, mut n, mut out) = (0u8, 0u8, Vec::new());
+    for bit in bits {
+        cur = (cur << 1) | bit as u8;
+        n += 1;
+        if n == 8 { out.push(cur); cur = 0; n = 0; }
+    }
+    if n > 0 { out.push(cur << (8 - n)); }
+    out
+}
+
+/* The fixed per-page sequence header, written into the first header_syms()
+ * symbols of every page so a shuffled stack of scans can be reassembled. */
+const HEADER_MAGIC: [u8; 2] = [b'O', b'P'];
+const HEADER_VERSION: u8 = 1;
+const HEADER_BYTES: usize = 15; /* magic(2) version(1) index(2) total(2) len(8) */
+
+/* Number of FEC symbols the header occupies for a given net symbol width. */
+fn header_syms(small: u64) -> u64 { (HEADER_BYTES as u64 * 8).div_ceil(small) }
+
+fn header_record(index: u16, total: u16, payload_len: u64) -> [u8; HEADER_BYTES] {
+    let mut r = [0u8; HEADER_BYTES];
+    r[0] = HEADER_MAGIC[0];
+    r[1] = HEADER_MAGIC[1];
+    r[2] = HEADER_VERSION;
+    r[3..5].copy_from_slice(&index.to_be_bytes());
+    r[5..7].copy_from_slice(&total.to_be_bytes());
+    r[7..15].copy_from_slice(&payload_len.to_be_bytes());
+    r
+}
+
+struct Header { version: u8, index: u16, total: u16, payload_len: u64 }
+
+fn parse_header(bytes: &[u8]) -> Option<Header> {
+    if bytes.len() < HEADER_BYTES || bytes[0..2] != HEADER_MAGIC { return None }
+    let be16 = |o: usize| ((bytes[o] as u16) << 8) | bytes[o + 1] as u16;
+    let mut len = 0u64;
+    for o in 7..15 { len = (len << 8) | bytes[o] as u64; }
+    Some(Header { version: bytes[2], index: be16(3), total: be16(5), payload_len: len })
+}
+
 pub fn parity(mut input: u64) -> u64 {
     let mut bit = (u64::max_value().count_ones()>>1);
     while bit > 0 {
@@ -112,15 +186,264 @@ pub fn parity(mut input: u64) -> u64 {
     input & 1
 }
 
-pub struct OptarWriter { buffer: image::ImageBuffer<image::Luma<u8>, Vec<u8>>, settings: Settings, accu: u64, hamming_symbol: u64, base_filename: String, file_number: u16 }
+/* A minimal RFC 1950/1951 codec. The encoder emits a single fixed-Huffman
+ * DEFLATE block from a greedy LZ77 parse wrapped in a zlib header and an
+ * Adler-32 trailer; the decoder handles the fixed and stored block types,
+ * which is everything this encoder produces. It exists so a page can be
+ * compressed before FEC without pulling in an external dependency. */
+
+/* DEFLATE length/distance code tables (RFC 1951 3.2.5). */
+static LENGTH_BASE:  [u16; 29] = [3,4,5,6,7,8,9,10,11,13,15,17,19,23,27,31,35,43,51,59,67,83,99,115,131,163,195,227,258];
+static LENGTH_EXTRA: [u8;  29] = [0,0,0,0,0,0,0,0,1,1,1,1,2,2,2,2,3,3,3,3,4,4,4,4,5,5,5,5,0];
+static DIST_BASE:    [u16; 30] = [1,2,3,4,5,7,9,13,17,25,33,49,65,97,129,193,257,385,513,769,1025,1537,2049,3073,4097,6145,8193,12289,16385,24577];
+static DIST_EXTRA:   [u8;  30] = [0,0,0,0,1,1,2,2,3,3,4,4,5,5,6,6,7,7,8,8,9,9,10,10,11,11,12,12,13,13];
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &x in data {
+        a = (a + x as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+/* Bits other than Huffman codes are packed LSB-first; Huffman codes MSB-first. */
+struct BitWriter { bytes: Vec<u8>, buf: u32, cnt: u8 }
+impl BitWriter {
+    fn new() -> BitWriter { BitWriter { bytes: Vec::new(), buf: 0, cnt: 0 } }
+    fn bits(&mut self, val: u32, n: u8) {
+        self.buf |= (val & ((1 << n) - 1)) << self.cnt;
+        self.cnt += n;
+        while self.cnt >= 8 { self.bytes.push(self.buf as u8); self.buf >>= 8; self.cnt -= 8; }
+    }
+    fn huff(&mut self, code: u16, n: u8) {
+        for k in (0..n).rev() { self.bits(((code >> k) & 1) as u32, 1); }
+    }
+    fn finish(mut self) -> Vec<u8> {
+        if self.cnt > 0 { self.bytes.push(self.buf as u8); }
+        self.bytes
+    }
+}
+
+/* Fixed-Huffman literal/length code for a symbol (RFC 1951 3.2.6). */
+fn fixed_litlen(sym: u16) -> (u16, u8) {
+    match sym {
+        0..=143   => (0x30 + sym, 8),
+        144..=255 => (0x190 + (sym - 144), 9),
+        256..=279 => (sym - 256, 7),
+        _         => (0xc0 + (sym - 280), 8),
+    }
+}
+
+fn deflate_compress(data: &[u8]) -> Vec<u8> {
+    let mut bw = BitWriter::new();
+    bw.bits(1, 1); /* BFINAL */
+    bw.bits(1, 2); /* BTYPE = fixed Huffman */
+    const CHAIN: usize = 32; /* Greedy search depth - fast rather than optimal */
+    let mut head = vec![-1isize; 1 << 15];
+    let mut prev = vec![-1isize; data.len().max(1)];
+    let hash = |i: usize| (((data[i] as usize) << 10) ^ ((data[i+1] as usize) << 5) ^ (data[i+2] as usize)) & ((1 << 15) - 1);
+    let insert = |i: usize, head: &mut Vec<isize>, prev: &mut Vec<isize>| {
+        if i + 3 <= data.len() { let h = hash(i); prev[i] = head[h]; head[h] = i as isize; }
+    };
+    let mut i = 0;
+    while i < data.len() {
+        let (mut best_len, mut best_dist) = (0usize, 0usize);
+        if i + 3 <= data.len() {
+            let max = (data.len() - i).min(258);
+            let mut j = head[hash(i)];
+            let mut chain = 0;
+            while j >= 0 && chain < CHAIN {
+                let jp = j as usize;
+                if i - jp <= 32768 {
+                    let mut l = 0;
+                    while l < max && data[jp + l] == data[i + l] { l += 1; }
+                    if l > best_len { best_len = l; best_dist = i - jp; }
+                }
+                j = prev[jp];
+                chain += 1;
+            }
+        }
+        if best_len >= 3 {
+            let li = LENGTH_BASE.iter().rposition(|&b| b as usize <= best_len).unwrap();
+            let (code, n) = fixed_litlen(257 + li as u16);
+            bw.huff(code, n);
+            bw.bits((best_len - LENGTH_BASE[li] as usize) as u32, LENGTH_EXTRA[li]);
+            let di = DIST_BASE.iter().rposition(|&b| b as usize <= best_dist).unwrap();
+            bw.huff(di as u16, 5);
+            bw.bits((best_dist - DIST_BASE[di] as usize) as u32, DIST_EXTRA[di]);
+            for k in i..i + best_len { insert(k, &mut head, &mut prev); }
+            i += best_len;
+        } else {
+            let (code, n) = fixed_litlen(data[i] as u16);
+            bw.huff(code, n);
+            insert(i, &mut head, &mut prev);
+            i += 1;
+        }
+    }
+    let (code, n) = fixed_litlen(256); /* End of block */
+    bw.huff(code, n);
+    bw.finish()
+}
+
+pub fn zlib_compress(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01]; /* CMF/FLG for deflate with a 32K window */
+    out.extend(deflate_compress(data));
+    let adler = adler32(data);
+    out.extend_from_slice(&[(adler >> 24) as u8, (adler >> 16) as u8, (adler >> 8) as u8, adler as u8]);
+    out
+}
+
+struct BitReader<'a> { data: &'a [u8], pos: usize, buf: u32, cnt: u8 }
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> BitReader<'a> { BitReader { data: data, pos: 0, buf: 0, cnt: 0 } }
+    fn bit(&mut self) -> std::io::Result<u32> {
+        if self.cnt == 0 {
+            if self.pos >= self.data.len() { return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "deflate stream truncated")); }
+            self.buf = self.data[self.pos] as u32;
+            self.pos += 1;
+            self.cnt = 8;
+        }
+        let b = self.buf & 1;
+        self.buf >>= 1;
+        self.cnt -= 1;
+        Ok(b)
+    }
+    fn bits(&mut self, n: u8) -> std::io::Result<u32> {
+        let mut v = 0u32;
+        for i in 0..n { v |= self.bit()? << i; }
+        Ok(v)
+    }
+    /* Fixed literal/length symbol: read bits MSB-first until the code resolves. */
+    fn fixed_symbol(&mut self) -> std::io::Result<u16> {
+        let mut code = 0u32;
+        for len in 1..=9 {
+            code = (code << 1) | self.bit()?;
+            match len {
+                7 if code <= 0b0010111 => return Ok(256 + code as u16),
+                8 if (0b00110000..=0b10111111).contains(&code) => return Ok((code - 0b00110000) as u16),
+                8 if (0b11000000..=0b11000111).contains(&code) => return Ok(280 + (code - 0b11000000) as u16),
+                9 if (0b110010000..=0b111111111).contains(&code) => return Ok(144 + (code - 0b110010000) as u16),
+                _ => {}
+            }
+        }
+        Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "bad fixed Huffman code"))
+    }
+}
+
+/* Returns the inflated bytes and the number of input bytes consumed up to the
+ * byte boundary following the final block, so a caller can find a trailer that
+ * sits after the compressed data even when extra padding trails it. */
+fn inflate(data: &[u8]) -> std::io::Result<(Vec<u8>, usize)> {
+    let mut br = BitReader::new(data);
+    let mut out = Vec::new();
+    loop {
+        let bfinal = br.bit()?;
+        let btype = br.bits(2)?;
+        match btype {
+            0 => { /* Stored */
+                br.cnt = 0; /* Skip to byte boundary */
+                if br.pos + 4 > data.len() { return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "stored header truncated")); }
+                let len = data[br.pos] as usize | ((data[br.pos + 1] as usize) << 8);
+                br.pos += 4; /* LEN + NLEN */
+                if br.pos + len > data.len() { return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "stored block truncated")); }
+                out.extend_from_slice(&data[br.pos..br.pos + len]);
+                br.pos += len;
+            }
+            1 => { /* Fixed Huffman */
+                loop {
+                    let sym = br.fixed_symbol()?;
+                    if sym == 256 { break }
+                    if sym < 256 { out.push(sym as u8); continue }
+                    let li = (sym - 257) as usize;
+                    if li >= LENGTH_BASE.len() { return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "reserved length code")); }
+                    let length = LENGTH_BASE[li] as usize + br.bits(LENGTH_EXTRA[li])? as usize;
+                    let di = br.bits(5)?.reverse_bits() as usize >> 27; /* 5-bit distance code, MSB-first */
+                    if di >= DIST_BASE.len() { return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "reserved distance code")); }
+                    let distance = DIST_BASE[di] as usize + br.bits(DIST_EXTRA[di])? as usize;
+                    if distance > out.len() { return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "distance past start of output")); }
+                    let start = out.len() - distance;
+                    for k in 0..length { let b = out[start + k]; out.push(b); }
+                }
+            }
+            _ => return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "unsupported DEFLATE block type")),
+        }
+        if bfinal == 1 { break }
+    }
+    /* br.pos already points past the last byte pulled in; the trailer is
+     * byte-aligned from there, so any leftover sub-byte bits are discarded. */
+    Ok((out, br.pos))
+}
+
+pub fn zlib_decompress(data: &[u8]) -> std::io::Result<Vec<u8>> {
+    if data.len() < 6 { return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "zlib stream too short")); }
+    let (out, consumed) = inflate(&data[2..])?;
+    let end = 2 + consumed;
+    if end + 4 > data.len() { return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "Adler-32 trailer truncated")); }
+    let t = &data[end..end + 4];
+    let trailer = ((t[0] as u32) << 24) | ((t[1] as u32) << 16) | ((t[2] as u32) << 8) | t[3] as u32;
+    if adler32(&out) != trailer { return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Adler-32 mismatch")); }
+    Ok(out)
+}
+
+/* Width in pixels of one bit-cell in the header strip. */
+const LABEL_CELL: u32 = 3;
+
+pub struct OptarWriter { buffer: image::ImageBuffer<image::Luma<u8>, Vec<u8>>, settings: Settings, accu: u64, hamming_symbol: u64, base_filename: String, file_number: u16, total_files: u16 }
 impl OptarWriter {
     fn new(settings: Settings, base_filename: Option<String>) -> OptarWriter {
-        OptarWriter { buffer: image::ImageBuffer::from_pixel(settings.width() as u32, settings.height() as u32, image::Luma([255u8])), settings: settings, accu: 1, hamming_symbol: 0, base_filename: base_filename.unwrap_or("optar_out".to_owned()), file_number: 0 }
+        OptarWriter { buffer: image::ImageBuffer::from_pixel(settings.width() as u32, settings.height() as u32, image::Luma([255u8])), settings: settings, accu: 1, hamming_symbol: 0, base_filename: base_filename.unwrap_or("optar_out".to_owned()), file_number: 0, total_files: 0 }
     }
 
     fn write_output(&mut self) -> std::io::Result<()> {
+        self.label();
         image::save_buffer(format!("{}_{:04}.png", self.base_filename, self.file_number), &self.buffer, self.settings.width() as u32, self.settings.height() as u32, image::ColorType::Gray(0))
     }
+
+    /* CRC-32 of this page's channel bits, read back out of the buffer in the
+     * same seq order seq2xy lays them down (dark pixel = 1) and packed
+     * MSB-first. The decoder recomputes the identical quantity from its
+     * thresholded samples, so the dark test here mirrors its `< thr`. */
+    fn channel_crc(&self) -> u32 {
+        let bits = (0..self.settings.total_bits()).map(|seq| {
+            let (x, y) = self.settings.seq2xy(seq).unwrap();
+            self.buffer.get_pixel((x + self.settings.border) as u32, (y + self.settings.border) as u32).data[0] < 128
+        });
+        crc32(&pack_bits(bits))
+    }
+
+    /* Render the machine-readable header strip reserved by text_height at the
+     * bottom of the page: the full settings string, this page's number and the
+     * document total, and a CRC-32 of the channel bits, serialised as a length
+     * byte followed by that ASCII header, one bit per LABEL_CELL cell (black=1)
+     * wrapped left-to-right, top-to-bottom so it fits in the strip's width. The
+     * decoder samples the cells back to flag pages whose bits fail the CRC; it
+     * locates the strip with, and keeps, the Settings it was given and does not
+     * configure itself from the settings string. */
+    fn label(&mut self) {
+        let header = format!("{} {}/{} {:08x}", self.settings.format_string(), self.file_number, self.total_files, self.channel_crc());
+        let mut record = Vec::with_capacity(1 + header.len());
+        record.push(header.len() as u8);
+        record.extend_from_slice(header.as_bytes());
+
+        let strip_top = (self.settings.border + self.settings.data_height()) as u32;
+        let x0 = self.settings.border as u32;
+        let cols = (self.buffer.width() - 2*x0) / LABEL_CELL;
+        let mut cell = 0u32;
+        for &byte in &record {
+            for bit in (0..8).rev() {
+                let shade = if (byte >> bit) & 1 == 1 { 0u8 } else { 255u8 };
+                let (bx, by) = (x0 + (cell % cols) * LABEL_CELL, strip_top + (cell / cols) * LABEL_CELL);
+                for dy in 0..LABEL_CELL {
+                    for dx in 0..LABEL_CELL {
+                        let (x, y) = (bx + dx, by + dy);
+                        if x < self.buffer.width() && y < self.buffer.height() { self.buffer.put_pixel(x, y, image::Luma([shade])); }
+                    }
+                }
+                cell += 1;
+            }
+        }
+    }
     /* Groups into two groups of bits, 0...bit-1 and bit..., and then makes
      * a gap with zero between them by shifting the higer bits up. */
     fn split(mut input: u64, bit: u8) -> u64 {
@@ -129,6 +452,21 @@ impl OptarWriter {
         high ^= input;
         (high << 1) | input
     }
+    /* The twelve rows of the standard 12x12 Golay matrix B, each a 12-bit mask. */
+    const GOLAY_B: [u64; 12] = [0o5073, 0o6435, 0o7216, 0o5507, 0o6643, 0o7321, 0o7550, 0o5664, 0o4732, 0o4355, 0o6166, 0o3777];
+    /* Extended binary Golay [24,12,8] encoder: data bits high, check bits low. */
+    fn golay(mut input: u64) -> u64 {
+        input &= (1u64 << FecOrder::Golay.small_bits()) - 1;
+        (input << 12) | Self::golay_check(input)
+    }
+    /* Check bit j is the parity of the data word ANDed with row j of B. */
+    fn golay_check(data: u64) -> u64 {
+        let mut check = 0u64;
+        for j in 0..12 {
+            check |= parity(data & Self::GOLAY_B[j]) << j;
+        }
+        check
+    }
     /* Thie bits are always stored in the LSB side of the register. Only the
      * lowest FEC_SMALLBITS are taken into account on input. */
     fn hamming(mut input: u64, order: u8) -> u64 {
@@ -170,8 +508,8 @@ impl OptarWriter {
         }
     }
     fn crosses(&mut self) {
-        for y in num::range_step(self.settings.border, self.settings.height()-self.settings.text_height-self.settings.border-2*self.settings.chalf, self.settings.cpitch) {
-            for x in num::range_step(self.settings.border, self.settings.width()-self.settings.border-2*self.settings.chalf, self.settings.cpitch) {
+        for y in num::range_step(self.settings.border, self.settings.height()-self.settings.text_height-self.settings.border, self.settings.cpitch) {
+            for x in num::range_step(self.settings.border, self.settings.width()-self.settings.border, self.settings.cpitch) {
                 self.cross(x as u32, y as u32);
             }
         }
@@ -180,25 +518,25 @@ impl OptarWriter {
         self.buffer = image::ImageBuffer::from_pixel(self.settings.width() as u32, self.settings.height() as u32, image::Luma([255u8]));
         self.border();
         self.crosses();
-        //self.label();
+        /* The label strip is filled in write_output, once the page's channel
+         * bits exist and their CRC can be taken. */
     }
     fn new_file(&mut self) -> std::io::Result<()> {
         if self.file_number > 0 { self.write_output()?; }
         assert!(self.file_number < 9999);
         self.file_number += 1;
         self.reformat_buffer();
+        self.accu = 1;
+        self.hamming_symbol = 0;
         Ok(())
     }
     /* Only the LSB is significant. Writes hamming-encoded bits. The sequence number
      * must not be out of range! */
-    fn write_channelbit(&mut self, mut bit: u8, seq: u64) {
-        bit &= 1u8;
-        bit.wrapping_sub(1);
+    fn write_channelbit(&mut self, bit: u8, seq: u64) {
         /* White=bit 0, black=bit 1 */
+        let shade = if bit & 1 == 1 { 0u8 } else { 255u8 };
         let (x, y) = self.settings.seq2xy(seq).unwrap();
-        // INCOMPLETE
-        self.buffer.put_pixel((x+self.settings.border) as u32, (y+self.settings.border) as u32, image::Luma([bit]));
-        //CHANGE THIS self.buffer[(x+self.settings.border)+(y+self.settings.border)*self.settings.width()] = bit;
+        self.buffer.put_pixel((x+self.settings.border) as u32, (y+self.settings.border) as u32, image::Luma([shade]));
     }
 
     /* That's the net channel capacity */
@@ -207,13 +545,11 @@ impl OptarWriter {
         self.accu |= (bit&1u8) as u64;
         if self.accu&(1u64<<self.settings.fec_order.small_bits()) != 0 {
             match self.settings.fec_order {
-                FecOrder::Golay => unimplemented!(),
+                FecOrder::Golay => self.accu = Self::golay(self.accu),
                 FecOrder::Hamming(x) => self.accu = Self::hamming(self.accu, x),
             }
-            if self.hamming_symbol >= self.settings.fec_syms() {
-                self.new_file()?;
-                self.hamming_symbol = 0;
-            }
+            /* Page rollover and the per-page header are driven by feed_data,
+             * which feeds exactly fec_syms symbols per page. */
             for shift in (0..self.settings.fec_order.large_bits()).rev() {
                 let bit = (self.accu>>shift) as u8;
                 let seq = self.hamming_symbol+(self.settings.fec_order.large_bits()-1-shift)*self.settings.fec_syms();
@@ -225,17 +561,496 @@ impl OptarWriter {
         Ok(())
     }
 
-    fn write_byte(&mut self, c: u8) -> std::io::Result<()>  {
-        for bit in (0..8).rev() {
-            self.write_payloadbit(c>>bit)?;
+    fn feed_data<R: std::io::Read>(&mut self, mut input_stream: R) -> std::io::Result<()> {
+        /* Gather the whole payload first so its length and the resulting page
+         * count are known before the first header is written. Compression, when
+         * enabled, happens here so the pages carry a self-contained zlib stream. */
+        let mut raw = Vec::new();
+        input_stream.read_to_end(&mut raw)?;
+        let payload = match self.settings.compress {
+            Compression::None => raw,
+            Compression::Deflate => zlib_compress(&raw),
+        };
+
+        let small = self.settings.fec_order.small_bits();
+        let fec_syms = self.settings.fec_syms();
+        let hdr_syms = header_syms(small);
+        let data_syms = fec_syms - hdr_syms;
+        let data_bits_per_page = data_syms * small;
+        let payload_bits = payload.len() as u64 * 8;
+        let total_pages = payload_bits.div_ceil(data_bits_per_page).max(1);
+        self.total_files = total_pages as u16;
+
+        let bit = |buf: &[u8], i: u64| (buf[(i / 8) as usize] >> (7 - i % 8)) & 1;
+        for page in 0..total_pages {
+            self.new_file()?;
+            /* The header fills whole symbols; any bits past the record are zero. */
+            let header = header_record(page as u16, total_pages as u16, payload.len() as u64);
+            for i in 0..hdr_syms * small {
+                self.write_payloadbit(if i < HEADER_BYTES as u64 * 8 { bit(&header, i) } else { 0 })?;
+            }
+            for i in 0..data_bits_per_page {
+                let g = page * data_bits_per_page + i;
+                self.write_payloadbit(if g < payload_bits { bit(&payload, g) } else { 0 })?;
+            }
         }
+        self.write_output()
+    }
+}
+
+/* Otsu's method: pick the 8-bit threshold that maximises between-class
+ * variance of a grayscale histogram. Returns the lowest gray level of the
+ * light class, so a sample `< thr` is dark. Falls back to mid-gray on an empty
+ * histogram. */
+fn otsu(hist: &[u64; 256]) -> u8 {
+    let total: u64 = hist.iter().sum();
+    if total == 0 { return 128 }
+    let sum: u64 = hist.iter().enumerate().map(|(i, &h)| i as u64 * h).sum();
+    let (mut wb, mut sumb, mut best, mut thr) = (0u64, 0u64, -1.0f64, 128usize);
+    for (t, &h) in hist.iter().enumerate() {
+        wb += h;
+        if wb == 0 { continue }
+        let wf = total - wb;
+        if wf == 0 { break }
+        sumb += t as u64 * h;
+        let mb = sumb as f64 / wb as f64;
+        let mf = (sum - sumb) as f64 / wf as f64;
+        let between = wb as f64 * wf as f64 * (mb - mf) * (mb - mf);
+        if between > best { best = between; thr = t + 1; }
+    }
+    thr as u8
+}
+
+/* One decoded page held until the whole document has been read: its sequence
+ * header and the net payload bits recovered from the symbols after it. */
+struct DecodedPage {
+    header: Header,
+    bits: Vec<u8>, /* One 0/1 per net payload bit, in document order */
+}
+
+/* The read side: the inverse of OptarWriter. Registers a scanned page,
+ * binarizes and de-interleaves it, runs FEC correction and buffers each page
+ * by its sequence header for finish() to reassemble. */
+pub struct OptarReader<W: Write> {
+    settings: Settings,
+    sink: W,
+    pages: Vec<DecodedPage>,
+    page: u16,
+    corrected_errors: u64,
+    uncorrectable_syms: u64,
+    crc_failures: u64,
+}
+
+impl<W: Write> OptarReader<W> {
+    fn new(settings: Settings, sink: W) -> OptarReader<W> {
+        OptarReader { settings: settings, sink: sink, pages: Vec::new(), page: 0, corrected_errors: 0, uncorrectable_syms: 0, crc_failures: 0 }
+    }
+
+    /* Extended Golay decoder. Returns the twelve data bits and the corrected
+     * error count, or None if no pattern of up to three errors explains the
+     * word. Four errors always give None; five or more can be miscorrected. */
+    fn golay_decode(recv: u64) -> Option<(u64, u32)> {
+        let s = OptarWriter::golay_check(recv >> 12) ^ (recv & 0xfff);
+        let err = if s.count_ones() <= 3 {
+            s
+        } else if let Some(i) = (0..12).find(|&i| (s ^ OptarWriter::GOLAY_B[i]).count_ones() <= 2) {
+            ((1u64 << i) << 12) | (s ^ OptarWriter::GOLAY_B[i])
+        } else {
+            let q = OptarWriter::golay_check(s);
+            if q.count_ones() <= 3 {
+                q << 12
+            } else if let Some(i) = (0..12).find(|&i| (q ^ OptarWriter::GOLAY_B[i]).count_ones() <= 2) {
+                ((q ^ OptarWriter::GOLAY_B[i]) << 12) | (1u64 << i)
+            } else {
+                return None
+            }
+        };
+        let corrected = recv ^ err;
+        Some(((corrected >> 12) & 0xfff, err.count_ones()))
+    }
+
+    /* Hamming decoder mirroring OptarWriter::hamming: recompute the syndrome
+     * from the same alternating masks, flip the indicated bit, then gather the
+     * data bits back out of the non-parity positions. */
+    fn hamming_decode(mut word: u64, order: u8) -> Option<(u64, u32)> {
+        let large = FecOrder::Hamming(order).large_bits();
+        let mut syndrome = 0u64;
+        for bit in 1..(order as u64 + 1) {
+            let x = 1u64 << (bit-1);
+            let mask = u64::from_str_radix({
+                let unit = "1".repeat(x as usize) + &"0".repeat(x as usize);
+                &unit.repeat((u64::max_value().count_ones() as usize)/unit.len())
+            }, 2).unwrap();
+            if parity(word & mask) != 0 { syndrome |= x; }
+        }
+        let mut errors = 0u32;
+        if syndrome != 0 {
+            if syndrome >= large { return None }
+            word ^= 1 << syndrome;
+            errors = 1;
+        }
+        let mut data = 0u64;
+        let mut di = 0;
+        for p in 1..large {
+            if p & (p-1) == 0 { continue } /* Skip the overall and power-of-two parity bits */
+            data |= ((word >> p) & 1) << di;
+            di += 1;
+        }
+        Some((data, errors))
+    }
+
+    fn decode_symbol(&self, word: u64) -> Option<(u64, u32)> {
+        match self.settings.fec_order {
+            FecOrder::Golay => Self::golay_decode(word),
+            FecOrder::Hamming(x) => Self::hamming_decode(word, x),
+        }
+    }
+
+    /* Score of the cross template centred on (cx,cy): the two black quadrants
+     * want dark pixels, the two white quadrants want light ones. The same
+     * checkerboard cross() paints. */
+    fn cross_score(&self, img: &image::ImageBuffer<image::Luma<u8>, Vec<u8>>, cx: i64, cy: i64) -> i64 {
+        let ch = self.settings.chalf as i64;
+        let mut score = 0i64;
+        for dy in -ch..ch {
+            for dx in -ch..ch {
+                let (px, py) = (cx+dx, cy+dy);
+                if px < 0 || py < 0 || px >= img.width() as i64 || py >= img.height() as i64 { continue }
+                let v = img.get_pixel(px as u32, py as u32).data[0] as i64;
+                let sign = if (dx < 0) == (dy < 0) { -1 } else { 1 };
+                score += sign * (v - 128);
+            }
+        }
+        score
+    }
+
+    /* Refine every expected cross centre by searching a window around its
+     * nominal grid position for the best template match. Returns pixel-space
+     * centres indexed [row][column]. */
+    fn detect_crosses(&self, img: &image::ImageBuffer<image::Luma<u8>, Vec<u8>>) -> Vec<Vec<(f64, f64)>> {
+        let (xc, yc) = (self.settings.xcrosses as usize, self.settings.ycrosses as usize);
+        let (cp, ch, b) = (self.settings.cpitch as i64, self.settings.chalf as i64, self.settings.border as i64);
+        let win = (self.settings.cpitch / 4) as i64 + 1;
+        let mut centers = vec![vec![(0.0, 0.0); xc]; yc];
+        for (j, row) in centers.iter_mut().enumerate() {
+            for (i, c) in row.iter_mut().enumerate() {
+                let (ex, ey) = (b + i as i64 * cp + ch, b + j as i64 * cp + ch);
+                let (mut best, mut bx, mut by) = (i64::min_value(), ex, ey);
+                for dy in -win..win+1 {
+                    for dx in -win..win+1 {
+                        let s = self.cross_score(img, ex+dx, ey+dy);
+                        if s > best { best = s; bx = ex+dx; by = ey+dy; }
+                    }
+                }
+                *c = (bx as f64, by as f64);
+            }
+        }
+        centers
+    }
+
+    /* Map a data coordinate (relative to the first cross, border excluded)
+     * onto an actual pixel by bilinearly interpolating the four detected
+     * cross centres bounding its grid cell. This absorbs skew, rotation and
+     * barrel distortion per cell. */
+    fn map(&self, x: f64, y: f64, centers: &[Vec<(f64, f64)>]) -> (f64, f64) {
+        let (cp, ch) = (self.settings.cpitch as f64, self.settings.chalf as f64);
+        let (xc, yc) = (self.settings.xcrosses as i64, self.settings.ycrosses as i64);
+        let i = (((x-ch)/cp).floor() as i64).max(0).min(xc-2) as usize;
+        let j = (((y-ch)/cp).floor() as i64).max(0).min(yc-2) as usize;
+        let u = (x - (i as f64 * cp + ch)) / cp;
+        let v = (y - (j as f64 * cp + ch)) / cp;
+        let lerp = |a: (f64,f64), b: (f64,f64), t: f64| (a.0 + (b.0-a.0)*t, a.1 + (b.1-a.1)*t);
+        let top = lerp(centers[j][i], centers[j][i+1], u);
+        let bot = lerp(centers[j+1][i], centers[j+1][i+1], u);
+        lerp(top, bot, v)
+    }
+
+    /* Bilinear grayscale sample with edge clamping. */
+    fn sample(img: &image::ImageBuffer<image::Luma<u8>, Vec<u8>>, px: f64, py: f64) -> u8 {
+        let x = px.max(0.0).min(img.width() as f64 - 1.0);
+        let y = py.max(0.0).min(img.height() as f64 - 1.0);
+        let (x0, y0) = (x.floor() as u32, y.floor() as u32);
+        let (x1, y1) = ((x0+1).min(img.width()-1), (y0+1).min(img.height()-1));
+        let (fx, fy) = (x - x0 as f64, y - y0 as f64);
+        let g = |xx, yy| img.get_pixel(xx, yy).data[0] as f64;
+        let top = g(x0,y0)*(1.0-fx) + g(x1,y0)*fx;
+        let bot = g(x0,y1)*(1.0-fx) + g(x1,y1)*fx;
+        (top*(1.0-fy) + bot*fy) as u8
+    }
+
+    /* Sample the header strip written by OptarWriter::label back into its ASCII
+     * bytes: a length byte followed by that many payload bytes, one bit per
+     * LABEL_CELL cell (black=1) wrapped across the strip rows. The strip sits
+     * below the last cross row, so the cells are read at their absolute page
+     * positions rather than through the payload's cross registration. Returns
+     * None if the length reads as an implausible 0 or 255. */
+    fn read_label(&self, img: &image::ImageBuffer<image::Luma<u8>, Vec<u8>>) -> Option<Vec<u8>> {
+        let x0 = self.settings.border as u32;
+        let strip_top = (self.settings.border + self.settings.data_height()) as u32;
+        let cols = (self.settings.width() as u32 - 2*x0) / LABEL_CELL;
+        let cell = |k: u32| -> u8 {
+            let cx = (x0 + (k % cols) * LABEL_CELL + LABEL_CELL/2).min(img.width()-1);
+            let cy = (strip_top + (k / cols) * LABEL_CELL + LABEL_CELL/2).min(img.height()-1);
+            if img.get_pixel(cx, cy).data[0] < 128 { 1 } else { 0 }
+        };
+        let read_byte = |base: u32| -> u8 { (0..8).fold(0u8, |acc, j| (acc << 1) | cell(base + j)) };
+        let len = read_byte(0);
+        if len == 0 || len == 0xff { return None }
+        Some((0..len as u32).map(|i| read_byte(8 + i * 8)).collect())
+    }
+
+    /* Reassemble the pages in header-index order, report gaps and duplicates,
+     * trim to the header's payload length and inflate before writing the sink. */
+    fn finish(&mut self) -> std::io::Result<()> {
+        if self.pages.is_empty() { return Ok(()); }
+        self.pages.sort_by_key(|p| p.header.index);
+
+        let total = self.pages.iter().map(|p| p.header.total).max().unwrap_or(0);
+        let payload_len = self.pages[0].header.payload_len;
+        let mut bits = Vec::new();
+        let mut next = 0u16;
+        for p in &self.pages {
+            if p.header.index < next { eprintln!("optar: duplicate or out-of-order page {}", p.header.index); continue }
+            while next < p.header.index { eprintln!("optar: missing page {}", next); next += 1; }
+            bits.extend_from_slice(&p.bits);
+            next += 1;
+        }
+        while next < total { eprintln!("optar: missing page {}", next); next += 1; }
+
+        bits.truncate((payload_len * 8) as usize);
+        if (bits.len() as u64) < payload_len * 8 { eprintln!("optar: document incomplete, {} of {} payload bytes recovered", bits.len() / 8, payload_len); }
+        let payload = pack_bits(bits.iter().map(|b| *b != 0));
+        let out = match self.settings.compress {
+            Compression::None => payload,
+            Compression::Deflate => zlib_decompress(&payload)?,
+        };
+        self.sink.write_all(&out)
+    }
+
+    /* Decode one scanned page, split off its sequence header and buffer the
+     * payload bits. Reports corrected errors, uncorrectable symbols and CRC. */
+    fn feed_image(&mut self, img: &image::ImageBuffer<image::Luma<u8>, Vec<u8>>) -> std::io::Result<()> {
+        let centers = self.detect_crosses(img);
+        /* First pass: sample every payload pixel and build a histogram for Otsu. */
+        let total = self.settings.total_bits();
+        let mut samples = Vec::with_capacity(total as usize);
+        let mut hist = [0u64; 256];
+        for seq in 0..total {
+            let (x, y) = self.settings.seq2xy(seq).unwrap();
+            let (px, py) = self.map(x as f64, y as f64, &centers);
+            let v = Self::sample(img, px, py);
+            hist[v as usize] += 1;
+            samples.push(v);
+        }
+        let thr = otsu(&hist);
+        /* Second pass: regroup channel bits into FEC symbols with the exact
+         * interleaving write_payloadbit used and decode each one. */
+        let fec_syms = self.settings.fec_syms();
+        let large = self.settings.fec_order.large_bits();
+        let small = self.settings.fec_order.small_bits();
+        let (mut corrected, mut uncorrectable) = (0u64, 0u64);
+        let mut netbits = Vec::with_capacity((fec_syms * small) as usize);
+        for k in 0..fec_syms {
+            let mut word = 0u64;
+            for p in 0..large {
+                let seq = k + p*fec_syms;
+                let bit = if samples[seq as usize] < thr { 1u64 } else { 0 }; /* black=1 */
+                word = (word << 1) | bit;
+            }
+            match self.decode_symbol(word) {
+                Some((data, errs)) => {
+                    corrected += errs as u64;
+                    for shift in (0..small).rev() { netbits.push(((data >> shift) & 1) as u8); }
+                }
+                None => {
+                    uncorrectable += 1;
+                    for _ in 0..small { netbits.push(0); }
+                }
+            }
+        }
+        self.corrected_errors += corrected;
+        self.uncorrectable_syms += uncorrectable;
+
+        /* Verify the page against its self-describing header strip: recompute
+         * the channel-bit CRC exactly as the writer did and compare it with
+         * the one rendered on the page. */
+        let actual = crc32(&pack_bits(samples.iter().map(|v| *v < thr)));
+        let crc_ok = match self.read_label(img).and_then(|r| String::from_utf8(r).ok()) {
+            Some(header) => header.rsplit(' ').next()
+                .and_then(|h| u32::from_str_radix(h, 16).ok())
+                .map(|claimed| claimed == actual)
+                .unwrap_or(false),
+            None => false,
+        };
+        if !crc_ok { self.crc_failures += 1; }
+
+        /* Split off the FEC-protected sequence header and keep the rest. */
+        let header_bits = ((header_syms(small) * small) as usize).min(netbits.len());
+        let header = pack_bits(netbits[..header_bits].iter().map(|b| *b != 0));
+        match parse_header(&header) {
+            Some(h) => {
+                if h.version != HEADER_VERSION { eprintln!("optar: page header version {} differs from {}", h.version, HEADER_VERSION); }
+                eprintln!("optar: page {} of {}: {} corrected bit errors, {} uncorrectable symbols, CRC {}",
+                    h.index, h.total, corrected, uncorrectable, if crc_ok { "ok" } else { "FAILED" });
+                self.pages.push(DecodedPage { header: h, bits: netbits.split_off(header_bits) });
+            }
+            None => eprintln!("optar: page {}: no valid sequence header, page dropped", self.page),
+        }
+        self.page += 1;
         Ok(())
     }
 
-    fn feed_data<R: std::io::Read>(&mut self, input_stream: R) -> std::io::Result<()> {
-        for c in input_stream.bytes() { self.write_byte(c?); }
-        /* Flush the FEC with zeroes */
-        for c in 1..self.settings.fec_order.small_bits() { self.write_payloadbit(0); }
-        self.write_output()
+    fn feed_data<P: AsRef<std::path::Path>>(&mut self, path: P) -> std::io::Result<()> {
+        let img = image::open(path).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?.to_luma();
+        self.feed_image(&img)
+    }
+
+    fn decode_file<P: AsRef<std::path::Path>>(settings: Settings, path: P, sink: W) -> std::io::Result<OptarReader<W>> {
+        let mut reader = OptarReader::new(settings, sink);
+        reader.feed_data(path)?;
+        reader.finish()?;
+        Ok(reader)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Compression, OptarReader, OptarWriter, Settings, crc32, header_record, parse_header, zlib_compress, zlib_decompress, HEADER_VERSION};
+
+    /* Two distinct codewords of a [24,12,8] code differ in at least 8 places.
+     * Encode a pseudo-random sample of 12-bit inputs (a plain LCG keeps the
+     * test dependency-free) and check every pair is at least that far apart. */
+    #[test]
+    fn golay_minimum_distance() {
+        let mut seed = 0x2545_f491_4f6c_dd1du64;
+        let sample: Vec<u64> = (0..64).map(|_| {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (seed >> 40) & 0xfff
+        }).collect();
+        for (i, &a) in sample.iter().enumerate() {
+            let ca = OptarWriter::golay(a);
+            for &b in &sample[i+1..] {
+                if a == b { continue }
+                let cb = OptarWriter::golay(b);
+                assert!((ca ^ cb).count_ones() >= 8, "distance {} for {:#05x}/{:#05x}", (ca ^ cb).count_ones(), a, b);
+            }
+        }
+    }
+
+    /* The decoder must recover the data and the exact error count for every
+     * pattern of up to three flipped channel bits, the correction limit of a
+     * [24,12,8] code. */
+    #[test]
+    fn golay_corrects_up_to_three_errors() {
+        for data in (0..4096).step_by(37) {
+            let codeword = OptarWriter::golay(data);
+            for a in 0..24 {
+                for b in a..24 {
+                    for c in b..24 {
+                        let err = (1u64<<a) | (1u64<<b) | (1u64<<c);
+                        let (recovered, errs) = OptarReader::<Vec<u8>>::golay_decode(codeword ^ err).expect("correctable");
+                        assert_eq!(recovered, data);
+                        assert_eq!(errs, err.count_ones());
+                    }
+                }
+            }
+        }
+    }
+
+    /* The compression stage must round-trip byte-for-byte, including the
+     * highly compressible and the incompressible cases, and must still decode
+     * once the FEC flush has appended trailing zero padding to the stream. */
+    #[test]
+    fn zlib_round_trips() {
+        let mut seed = 0x9e37_79b9_7f4a_7c15u64;
+        let cases: Vec<Vec<u8>> = vec![
+            Vec::new(),
+            b"optar".to_vec(),
+            vec![0x42u8; 4096],
+            b"the quick brown fox jumps over the lazy dog. ".iter().cloned().cycle().take(10000).collect(),
+            (0..4096).map(|_| {
+                seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                (seed >> 33) as u8
+            }).collect(),
+        ];
+        for case in &cases {
+            let mut stream = zlib_compress(case);
+            assert_eq!(&zlib_decompress(&stream).unwrap(), case);
+            stream.extend_from_slice(&[0u8; 16]); /* As the FEC flush would */
+            assert_eq!(&zlib_decompress(&stream).unwrap(), case);
+        }
+    }
+
+    /* The CRC-32 rendered on each page's header strip uses the standard
+     * reflected polynomial; check it against the well-known "check" value. */
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    /* The sequence header must survive its big-endian serialisation so the
+     * decoder reads back the exact index, total and payload length. */
+    #[test]
+    fn header_round_trips() {
+        for &(index, total, len) in &[(0u16, 1u16, 0u64), (3, 10, 65535), (258, 259, 0x0123_4567_89ab_cdef)] {
+            let record = header_record(index, total, len);
+            let h = parse_header(&record).expect("valid header");
+            assert_eq!((h.version, h.index, h.total, h.payload_len), (HEADER_VERSION, index, total, len));
+        }
+        assert!(parse_header(b"XX not optar").is_none());
+    }
+
+    /* The header strip read_label samples must carry the full record label
+     * renders, wrapping rows rather than truncating at the page width. */
+    #[test]
+    fn label_round_trips() {
+        let mut writer = OptarWriter::new(Settings { xcrosses: 12, ycrosses: 12, .. Settings::default() }, None);
+        writer.file_number = 3;
+        writer.total_files = 7;
+        writer.label();
+        let reader = OptarReader::new(Settings { xcrosses: 12, ycrosses: 12, .. Settings::default() }, Vec::new());
+        let got = String::from_utf8(reader.read_label(&writer.buffer).expect("label decodes")).unwrap();
+        assert_eq!(got, format!("{} 3/7 {:08x}", writer.settings.format_string(), writer.channel_crc()));
+    }
+
+    /* Render pages with the writer and decode them back. A clean rendered page
+     * carries no scan distortion, so the recovered bytes must equal the input
+     * exactly, both raw and with compression on. */
+    fn round_trip(compress: Compression, payload: &[u8]) {
+        let base = std::env::temp_dir().join(format!("optar_rt_{}_{:?}", std::process::id(), compress));
+        let base = base.to_str().unwrap().to_owned();
+        {
+            let mut writer = OptarWriter::new(Settings { xcrosses: 12, ycrosses: 12, compress, .. Settings::default() }, Some(base.clone()));
+            writer.feed_data(payload).unwrap();
+        }
+        let mut reader = OptarReader::new(Settings { xcrosses: 12, ycrosses: 12, compress, .. Settings::default() }, Vec::new());
+        let mut n = 1;
+        loop {
+            let path = format!("{}_{:04}.png", base, n);
+            if !std::path::Path::new(&path).exists() { break }
+            reader.feed_data(&path).unwrap();
+            std::fs::remove_file(&path).ok();
+            n += 1;
+        }
+        reader.finish().unwrap();
+        assert_eq!(reader.sink.as_slice(), payload);
+    }
+
+    #[test]
+    fn writer_reader_round_trip() {
+        round_trip(Compression::None, b"optar end-to-end round trip");
+    }
+
+    /* A document produced with compression on must decode byte-for-byte, even
+     * when the compressed stream spills across several pages. */
+    #[test]
+    fn writer_reader_round_trip_compressed() {
+        let mut seed = 0x1234_5678_9abc_def0u64;
+        let payload: Vec<u8> = (0..6000).map(|_| {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (seed >> 33) as u8
+        }).collect();
+        round_trip(Compression::Deflate, &payload);
     }
 }
\ No newline at end of file